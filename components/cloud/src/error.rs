@@ -5,10 +5,12 @@ use std::{
     fmt::{self, Debug, Display},
     io::Error as IoError,
     result,
+    time::Duration,
 };
 
 use error_code::{self, ErrorCode, ErrorCodeExt};
 use protobuf::ProtobufError;
+use rand::Rng;
 use thiserror::Error;
 use tikv_util::stream::RetryError;
 
@@ -35,6 +37,10 @@ pub enum Error {
     ApiAuthentication(Box<dyn error::Error + Sync + Send>),
     #[error("Key error: {0}")]
     KmsError(KmsError),
+    #[error("Signature error: {0}")]
+    Signature(SignatureError),
+    #[error("Throttled, retry after {retry_after:?}")]
+    Throttled { retry_after: Option<Duration> },
 }
 
 impl ErrorTrait for Error {}
@@ -47,6 +53,8 @@ pub enum KmsError {
     EmptyKey(String),
     #[error("Kms error {0}")]
     Other(OtherError),
+    #[error("Kms throttled, retry after {0:?}")]
+    Throttled(Option<Duration>),
 }
 
 impl From<KmsError> for Error {
@@ -55,6 +63,22 @@ impl From<KmsError> for Error {
     }
 }
 
+/// A signature produced by an untrusted key, or a file whose signed digest
+/// no longer matches its contents.
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("signature does not match file contents")]
+    Tampered,
+    #[error("signing key {0} is not in the trusted key set")]
+    UntrustedKey(String),
+}
+
+impl From<SignatureError> for Error {
+    fn from(e: SignatureError) -> Self {
+        Error::Signature(e)
+    }
+}
+
 impl From<Error> for IoError {
     fn from(err: Error) -> IoError {
         match err {
@@ -70,6 +94,7 @@ impl ErrorCodeExt for KmsError {
             KmsError::WrongMasterKey(_) => error_code::cloud::WRONG_MASTER_KEY,
             KmsError::EmptyKey(_) => error_code::cloud::INVALID_INPUT,
             KmsError::Other(_) => error_code::cloud::UNKNOWN,
+            KmsError::Throttled(_) => error_code::cloud::UNKNOWN,
         }
     }
 }
@@ -85,6 +110,8 @@ impl ErrorCodeExt for Error {
             Error::ApiNotFound(_) => error_code::cloud::API_NOT_FOUND,
             Error::ApiAuthentication(_) => error_code::cloud::API_AUTHENTICATION,
             Error::KmsError(e) => e.error_code(),
+            Error::Signature(_) => error_code::cloud::UNKNOWN,
+            Error::Throttled { .. } => error_code::cloud::UNKNOWN,
         }
     }
 }
@@ -102,16 +129,80 @@ impl RetryError for Error {
             Error::ApiNotFound(_) => false,
             Error::ApiAuthentication(_) => false,
             Error::KmsError(e) => e.is_retryable(),
+            // A bad or missing signature will never become valid by retrying.
+            Error::Signature(_) => false,
+            Error::Throttled { .. } => true,
+        }
+    }
+}
+
+/// Base and cap for the exponential backoff used when a retryable error
+/// carries no server-provided `retry_after`.
+const BASE_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long to wait before retrying a [`RetryError`], so a KMS/cloud API
+/// storm backs off gracefully instead of retrying in a tight loop.
+/// `tikv_util::stream::RetryError` itself lives outside this crate, so this
+/// is a separate extension trait rather than a method added to it directly;
+/// implementing it is the only extra step beyond `RetryError` itself.
+pub trait BackoffHint: RetryError {
+    /// Returns `None` for errors that aren't retryable at all. The default
+    /// applies exponential backoff with jitter, scaled by `attempt`
+    /// (0-indexed), and does not look for a server-provided retry-after;
+    /// the implementations for [`Error`] and [`KmsError`] override it to
+    /// return their `Throttled` variants' retry-after when one is present.
+    fn backoff_hint(&self, attempt: u32) -> Option<Duration> {
+        if !self.is_retryable() {
+            return None;
         }
+        Some(exponential_backoff_with_jitter(attempt))
     }
 }
 
+impl BackoffHint for Error {
+    fn backoff_hint(&self, attempt: u32) -> Option<Duration> {
+        if !self.is_retryable() {
+            return None;
+        }
+        match self {
+            Error::Throttled {
+                retry_after: Some(d),
+            } => Some(*d),
+            Error::KmsError(e) => e.backoff_hint(attempt),
+            _ => Some(exponential_backoff_with_jitter(attempt)),
+        }
+    }
+}
+
+impl BackoffHint for KmsError {
+    fn backoff_hint(&self, attempt: u32) -> Option<Duration> {
+        if !self.is_retryable() {
+            return None;
+        }
+        match self {
+            KmsError::Throttled(Some(d)) => Some(*d),
+            _ => Some(exponential_backoff_with_jitter(attempt)),
+        }
+    }
+}
+
+fn exponential_backoff_with_jitter(attempt: u32) -> Duration {
+    let backoff = BASE_BACKOFF
+        .checked_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF);
+    let jittered_millis = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64);
+    Duration::from_millis(jittered_millis)
+}
+
 impl RetryError for KmsError {
     fn is_retryable(&self) -> bool {
         match self {
             KmsError::WrongMasterKey(_) => false,
             KmsError::EmptyKey(_) => false,
             KmsError::Other(e) => e.retryable,
+            KmsError::Throttled(_) => true,
         }
     }
 }
@@ -145,3 +236,61 @@ impl fmt::Display for OtherError {
         write!(f, "{}", self.err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttled_is_retryable() {
+        let err = Error::Throttled {
+            retry_after: Some(Duration::from_secs(5)),
+        };
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_error_backoff_hint_honors_server_retry_after() {
+        let err = Error::Throttled {
+            retry_after: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(err.backoff_hint(0), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_error_backoff_hint_none_when_not_retryable() {
+        let err = Error::ApiNotFound(Box::new(IoError::other("missing")));
+        assert_eq!(err.backoff_hint(0), None);
+    }
+
+    #[test]
+    fn test_error_backoff_hint_falls_back_to_jittered_exponential() {
+        let err = Error::ApiTimeout(Box::new(IoError::other("timeout")));
+        let hint = err.backoff_hint(3).unwrap();
+        assert!(hint <= MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_kms_error_throttled_is_retryable() {
+        let err = KmsError::Throttled(Some(Duration::from_secs(2)));
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn test_kms_error_backoff_hint_honors_retry_after() {
+        let err = KmsError::Throttled(Some(Duration::from_secs(2)));
+        assert_eq!(err.backoff_hint(0), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_kms_error_backoff_hint_none_when_not_retryable() {
+        let err = KmsError::EmptyKey("master".to_string());
+        assert_eq!(err.backoff_hint(0), None);
+    }
+
+    #[test]
+    fn test_error_kms_variant_delegates_backoff_hint_to_inner() {
+        let err = Error::KmsError(KmsError::Throttled(Some(Duration::from_secs(9))));
+        assert_eq!(err.backoff_hint(0), Some(Duration::from_secs(9)));
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! A combinator reader that dispatches a URI to the backend that handles its
+//! scheme, and, for schemes with more than one registered backend, falls
+//! through to the next one of that scheme when `open` or a read fails (e.g.
+//! a local file-cache backend first, then a remote object-store-backed
+//! reader for the same `file://` tier).
+//!
+//! This gives byte-level access only; it has no notion of SST structure
+//! (key/value iteration, checksums). A concrete engine's `SstReader` is not
+//! meant to be one itself — instead, `engine_traits::sst::SstReader::from_uri`
+//! takes a `fetch_remote` callback for its non-`file://` schemes, and a
+//! caller wires that callback to a [`TieredReader`] (read to a local temp
+//! file, then hand the path back so `SstReader::open` can read it as usual).
+
+use std::io::{self, Read};
+
+use tikv_util::stream::RetryError;
+
+use crate::error::Error;
+
+/// A storage backend capable of opening a readable handle for a URI this
+/// backend recognizes.
+pub trait Backend: Send + Sync {
+    /// The URI scheme this backend handles, e.g. `"file"`, `"s3"`.
+    fn scheme(&self) -> &str;
+
+    fn open(&self, uri: &str) -> crate::Result<Box<dyn Read + Send>>;
+}
+
+/// Which backend kind a URI's scheme dispatches to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    File,
+    S3,
+    Gcs,
+    AzBlob,
+}
+
+impl BackendKind {
+    fn scheme(self) -> &'static str {
+        match self {
+            BackendKind::File => "file",
+            BackendKind::S3 => "s3",
+            BackendKind::Gcs => "gs",
+            BackendKind::AzBlob => "azblob",
+        }
+    }
+}
+
+/// Picks a [`BackendKind`] from a URI's scheme, e.g. `s3://bucket/key`.
+pub fn backend_kind_from_uri(uri: &str) -> Option<BackendKind> {
+    let scheme = uri.split_once("://")?.0;
+    match scheme {
+        "file" => Some(BackendKind::File),
+        "s3" => Some(BackendKind::S3),
+        "gs" => Some(BackendKind::Gcs),
+        "azblob" => Some(BackendKind::AzBlob),
+        _ => None,
+    }
+}
+
+/// Wraps a set of backends, each registered under the scheme it serves.
+/// `open` dispatches a URI to only the backends registered for its own
+/// scheme (via [`backend_kind_from_uri`]/[`Backend::scheme`]), trying them in
+/// registration order and falling through to the next whenever one fails to
+/// open, or — via the [`Read`] wrapper it returns — fails a read partway
+/// through the stream. Errors are mapped into the existing cloud [`Error`]
+/// enum so the [`RetryError`] classification already defined there drives
+/// which `open` failures are worth falling back on versus surfacing
+/// immediately; a failed read always falls through to the next backend, if
+/// any remain.
+pub struct TieredReader {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl TieredReader {
+    pub fn new(backends: Vec<Box<dyn Backend>>) -> TieredReader {
+        TieredReader { backends }
+    }
+
+    fn backends_for(&self, kind: BackendKind) -> Vec<&dyn Backend> {
+        self.backends
+            .iter()
+            .map(|b| b.as_ref())
+            .filter(|b| b.scheme() == kind.scheme())
+            .collect()
+    }
+
+    /// Opens `uri` against the backends registered for its scheme, in order,
+    /// returning the first success wrapped in a [`FallbackReader`] that can
+    /// still fall through to the next candidate if a read fails later.
+    /// Stops and returns immediately on an error that isn't retryable (e.g.
+    /// `ApiNotFound`), since a different backend won't fix an inherently
+    /// missing object; keeps falling through on retryable ones.
+    pub fn open(&self, uri: &str) -> crate::Result<Box<dyn Read + Send + '_>> {
+        let kind = backend_kind_from_uri(uri).ok_or_else(|| {
+            Error::Io(io::Error::other(format!(
+                "no backend registered for the scheme of {}",
+                uri
+            )))
+        })?;
+        let candidates = self.backends_for(kind);
+        if candidates.is_empty() {
+            return Err(Error::Io(io::Error::other(format!(
+                "no backend registered for the scheme of {}",
+                uri
+            ))));
+        }
+
+        let (reader, remaining) = Self::open_first_available(&candidates, uri)?;
+        Ok(Box::new(FallbackReader {
+            uri: uri.to_string(),
+            current: reader,
+            remaining,
+            delivered: 0,
+        }))
+    }
+
+    /// Tries `candidates` in order, returning the first successful open
+    /// alongside the backends after it (kept around so a read failure can
+    /// still fall through to them).
+    fn open_first_available<'a>(
+        candidates: &[&'a dyn Backend],
+        uri: &str,
+    ) -> crate::Result<(Box<dyn Read + Send>, Vec<&'a dyn Backend>)> {
+        let mut last_err = None;
+        for (idx, backend) in candidates.iter().enumerate() {
+            match backend.open(uri) {
+                Ok(reader) => return Ok((reader, candidates[idx + 1..].to_vec())),
+                Err(e) => {
+                    let retryable = e.is_retryable();
+                    last_err = Some(e);
+                    if !retryable {
+                        break;
+                    }
+                }
+            }
+        }
+        Err(last_err
+            .unwrap_or_else(|| Error::Io(io::Error::other(format!("no backend could open {}", uri)))))
+    }
+}
+
+/// Wraps the currently-open backend's reader and, on a read error, opens the
+/// next candidate backend (if any remain), reads and discards from it the
+/// bytes already returned to the caller, and continues the read from there.
+/// Backends here are assumed to serve immutable, fully-written objects, so
+/// the fresh handle's bytes past that point are exactly the ones the caller
+/// is still missing; the skip is done by reading, not by a range request.
+struct FallbackReader<'a> {
+    uri: String,
+    current: Box<dyn Read + Send>,
+    remaining: Vec<&'a dyn Backend>,
+    /// Bytes already returned to the caller.
+    delivered: u64,
+}
+
+impl Read for FallbackReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.current.read(buf) {
+                Ok(n) => {
+                    self.delivered += n as u64;
+                    return Ok(n);
+                }
+                Err(e) => {
+                    let Some((&next, rest)) = self.remaining.split_first() else {
+                        return Err(e);
+                    };
+                    self.remaining = rest.to_vec();
+                    if let Ok(mut reader) = next.open(&self.uri) {
+                        let skipped =
+                            io::copy(&mut (&mut reader).take(self.delivered), &mut io::sink());
+                        if matches!(skipped, Ok(n) if n == self.delivered) {
+                            self.current = reader;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FailingBackend {
+        scheme: &'static str,
+        err: fn() -> Error,
+    }
+
+    impl Backend for FailingBackend {
+        fn scheme(&self) -> &str {
+            self.scheme
+        }
+
+        fn open(&self, _uri: &str) -> crate::Result<Box<dyn Read + Send>> {
+            Err((self.err)())
+        }
+    }
+
+    struct OkBackend {
+        scheme: &'static str,
+        content: &'static [u8],
+    }
+
+    impl Backend for OkBackend {
+        fn scheme(&self) -> &str {
+            self.scheme
+        }
+
+        fn open(&self, _uri: &str) -> crate::Result<Box<dyn Read + Send>> {
+            Ok(Box::new(io::Cursor::new(self.content.to_vec())))
+        }
+    }
+
+    /// A backend whose reader fails on its very first read, to exercise
+    /// mid-stream fallback.
+    struct ReadFailsOnceBackend {
+        scheme: &'static str,
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("simulated read failure"))
+        }
+    }
+
+    impl Backend for ReadFailsOnceBackend {
+        fn scheme(&self) -> &str {
+            self.scheme
+        }
+
+        fn open(&self, _uri: &str) -> crate::Result<Box<dyn Read + Send>> {
+            Ok(Box::new(FailingReader))
+        }
+    }
+
+    #[test]
+    fn test_backend_kind_from_uri() {
+        assert_eq!(backend_kind_from_uri("file:///tmp/a"), Some(BackendKind::File));
+        assert_eq!(backend_kind_from_uri("s3://bucket/key"), Some(BackendKind::S3));
+        assert_eq!(backend_kind_from_uri("gs://bucket/key"), Some(BackendKind::Gcs));
+        assert_eq!(
+            backend_kind_from_uri("azblob://bucket/key"),
+            Some(BackendKind::AzBlob)
+        );
+        assert_eq!(backend_kind_from_uri("not-a-uri"), None);
+    }
+
+    #[test]
+    fn test_dispatches_by_scheme_rather_than_list_order() {
+        // The file backend is registered first, but an s3:// URI must never
+        // reach it.
+        let reader = TieredReader::new(vec![
+            Box::new(FailingBackend {
+                scheme: "file",
+                err: || Error::ApiNotFound(Box::new(io::Error::other("should never be called"))),
+            }),
+            Box::new(OkBackend {
+                scheme: "s3",
+                content: b"ok",
+            }),
+        ]);
+        let mut buf = Vec::new();
+        reader
+            .open("s3://bucket/key")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"ok");
+    }
+
+    #[test]
+    fn test_falls_through_to_next_backend_of_same_scheme_on_open_failure() {
+        let reader = TieredReader::new(vec![
+            Box::new(FailingBackend {
+                scheme: "s3",
+                err: || Error::ApiTimeout(Box::new(io::Error::other("timeout"))),
+            }),
+            Box::new(OkBackend {
+                scheme: "s3",
+                content: b"ok",
+            }),
+        ]);
+        let mut buf = Vec::new();
+        reader
+            .open("s3://bucket/key")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"ok");
+    }
+
+    #[test]
+    fn test_stops_on_non_retryable_open_failure() {
+        let reader = TieredReader::new(vec![
+            Box::new(FailingBackend {
+                scheme: "file",
+                err: || Error::ApiNotFound(Box::new(io::Error::other("not found"))),
+            }),
+            Box::new(OkBackend {
+                scheme: "file",
+                content: b"ok",
+            }),
+        ]);
+        assert!(reader.open("file:///missing").is_err());
+    }
+
+    #[test]
+    fn test_errors_when_no_backend_registered_for_scheme() {
+        let reader = TieredReader::new(vec![Box::new(OkBackend {
+            scheme: "file",
+            content: b"ok",
+        })]);
+        assert!(reader.open("s3://bucket/key").is_err());
+    }
+
+    #[test]
+    fn test_falls_through_to_next_backend_on_read_failure() {
+        let reader = TieredReader::new(vec![
+            Box::new(ReadFailsOnceBackend { scheme: "s3" }),
+            Box::new(OkBackend {
+                scheme: "s3",
+                content: b"ok-after-fallback",
+            }),
+        ]);
+        let mut buf = Vec::new();
+        reader
+            .open("s3://bucket/key")
+            .unwrap()
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(buf, b"ok-after-fallback");
+    }
+}
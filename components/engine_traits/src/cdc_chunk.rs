@@ -0,0 +1,194 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Content-defined chunking for SST upload deduplication.
+//!
+//! Splits a byte stream into variable-sized chunks using a Gear-hash rolling
+//! window with normalized chunking, so identical byte ranges shared across
+//! files land on identical chunk boundaries and can be uploaded once. Cut
+//! points are a function of file content only, not of read buffer
+//! boundaries, which is what makes the chunking deterministic regardless of
+//! how the caller feeds bytes in.
+
+/// One chunk's position and content digest within the original file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkRecord {
+    pub offset: u64,
+    pub len: u64,
+    pub digest: [u8; 32],
+}
+
+/// The manifest the uploader consults to skip chunks the remote digest index
+/// already has, and the importer uses to reconstruct the file from chunks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkManifest {
+    pub chunks: Vec<ChunkRecord>,
+}
+
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+const TARGET_CHUNK_SIZE: usize = 64 * 1024;
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+// Number of low bits that must be zero to cut, before/after the target size.
+// mask_small is stricter (more bits) so cuts are rarer before the target is
+// reached; mask_large is looser so the chunk doesn't run all the way to
+// MAX_CHUNK_SIZE on every input.
+const MASK_SMALL: u64 = (1 << 15) - 1;
+const MASK_LARGE: u64 = (1 << 11) - 1;
+
+/// A 256-entry table of random 64-bit values used to roll the Gear hash.
+/// Fixed so chunking is reproducible across runs and machines.
+fn gear_table() -> &'static [u64; 256] {
+    static TABLE: std::sync::OnceLock<[u64; 256]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        // Deterministically derived, not cryptographic: a splitmix64 stream
+        // seeded with a fixed constant.
+        let mut table = [0u64; 256];
+        let mut x: u64 = 0x9E3779B97F4A7C15;
+        for slot in table.iter_mut() {
+            x = x.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            *slot = z ^ (z >> 31);
+        }
+        table
+    })
+}
+
+/// Streaming Gear-hash chunker: feed it bytes via [`Chunker::write`] in
+/// whatever buffer sizes are convenient and it emits cut points
+/// independently of those boundaries.
+#[derive(Default)]
+pub struct Chunker {
+    h: u64,
+    current: Vec<u8>,
+    chunks: Vec<ChunkRecord>,
+    base_offset: u64,
+}
+
+impl Chunker {
+    pub fn new() -> Chunker {
+        Chunker::default()
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        let table = gear_table();
+        for &byte in data {
+            self.h = (self.h << 1).wrapping_add(table[byte as usize]);
+            self.current.push(byte);
+
+            let len = self.current.len();
+            if len < MIN_CHUNK_SIZE {
+                continue;
+            }
+            let mask = if len < TARGET_CHUNK_SIZE {
+                MASK_SMALL
+            } else {
+                MASK_LARGE
+            };
+            if self.h & mask == 0 || len >= MAX_CHUNK_SIZE {
+                self.cut();
+            }
+        }
+    }
+
+    fn cut(&mut self) {
+        let digest = *blake3::hash(&self.current).as_bytes();
+        let len = self.current.len() as u64;
+        self.chunks.push(ChunkRecord {
+            offset: self.base_offset,
+            len,
+            digest,
+        });
+        self.base_offset += len;
+        self.current.clear();
+        self.h = 0;
+    }
+
+    /// Flushes whatever's left as the final, possibly short, chunk.
+    pub fn finish(mut self) -> ChunkManifest {
+        if !self.current.is_empty() {
+            self.cut();
+        }
+        ChunkManifest {
+            chunks: self.chunks,
+        }
+    }
+}
+
+/// Chunks a complete in-memory buffer in one call.
+pub fn chunk_bytes(data: &[u8]) -> ChunkManifest {
+    let mut chunker = Chunker::new();
+    chunker.write(data);
+    chunker.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic_regardless_of_buffer_boundaries() {
+        let data: Vec<u8> = (0..500_000u32).map(|i| (i % 251) as u8).collect();
+
+        let whole = chunk_bytes(&data);
+
+        let mut piecewise = Chunker::new();
+        for piece in data.chunks(777) {
+            piecewise.write(piece);
+        }
+        let piecewise = piecewise.finish();
+
+        assert_eq!(whole, piecewise);
+    }
+
+    #[test]
+    fn test_final_short_chunk_is_flushed() {
+        let data = vec![7u8; 100];
+        let manifest = chunk_bytes(&data);
+        assert_eq!(manifest.chunks.len(), 1);
+        assert_eq!(manifest.chunks[0].len, 100);
+    }
+
+    #[test]
+    fn test_chunks_cover_contiguous_offsets() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 97) as u8).collect();
+        let manifest = chunk_bytes(&data);
+        let mut expect_offset = 0u64;
+        for c in &manifest.chunks {
+            assert_eq!(c.offset, expect_offset);
+            assert!(c.len as usize >= MIN_CHUNK_SIZE || expect_offset + c.len == data.len() as u64);
+            expect_offset += c.len;
+        }
+        assert_eq!(expect_offset, data.len() as u64);
+    }
+
+    #[test]
+    fn test_identical_ranges_produce_identical_chunks() {
+        // Pseudo-random bytes: a short repeating pattern has too few distinct
+        // Gear windows to ever satisfy a mask, so it would never cut.
+        let mut x: u32 = 12345;
+        let shared: Vec<u8> = (0..100_000)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect();
+        let mut file_a = shared.clone();
+        file_a.extend(vec![1u8; 10_000]);
+        let mut file_b = vec![9u8; 5_000];
+        file_b.extend(shared.clone());
+
+        let manifest_a = chunk_bytes(&file_a);
+        let manifest_b = chunk_bytes(&file_b);
+
+        let digests_a: std::collections::HashSet<_> =
+            manifest_a.chunks.iter().map(|c| c.digest).collect();
+        let digests_b: std::collections::HashSet<_> =
+            manifest_b.chunks.iter().map(|c| c.digest).collect();
+        assert!(
+            digests_a.intersection(&digests_b).count() > 0,
+            "expected at least one chunk shared between the two files"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Fast-Add-Peer (FAP): bootstrap a newly added peer from an already-present
+//! local/source dataset instead of always waiting on a full RocksDB snapshot
+//! sent over gRPC.
+//!
+//! While a FAP build is in flight for a region, the normal `MsgSnapshot`
+//! pipeline is suppressed so the two bootstrap routes don't race each other;
+//! once FAP completes (or explicitly falls back), the classic path resumes.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use kvproto::raft_serverpb::RaftMessage;
+use raft::eraftpb::MessageType;
+
+/// `raft_store` config governing fast-add-peer: whether it's used at all,
+/// and how long a build may run before giving up and falling back to the
+/// classic full-snapshot path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FastAddPeerConfig {
+    pub enable_fast_add_peer: bool,
+    pub fallback_timeout_millis: u64,
+}
+
+impl Default for FastAddPeerConfig {
+    fn default() -> FastAddPeerConfig {
+        FastAddPeerConfig {
+            enable_fast_add_peer: false,
+            fallback_timeout_millis: 10_000,
+        }
+    }
+}
+
+/// Per-region bookkeeping for the fast-add-peer bootstrap race against the
+/// classic snapshot path.
+#[derive(Default)]
+pub struct CachedRegionInfo {
+    /// Set once the peer is known to be bootstrapped/initialized, or FAP has
+    /// explicitly given up and fallen back to the classic path.
+    inited_or_fallback: AtomicBool,
+    /// Unix timestamp (millis) a FAP build started, 0 when inactive.
+    fast_add_peer_start: AtomicU64,
+    /// Whether a FAP-built snapshot has already been emitted for this
+    /// region.
+    snapshot_inflight: AtomicBool,
+}
+
+impl CachedRegionInfo {
+    pub fn new() -> CachedRegionInfo {
+        CachedRegionInfo::default()
+    }
+
+    pub fn is_inited_or_fallback(&self) -> bool {
+        self.inited_or_fallback.load(Ordering::SeqCst)
+    }
+
+    pub fn mark_inited_or_fallback(&self) {
+        self.inited_or_fallback.store(true, Ordering::SeqCst);
+    }
+
+    pub fn start_fast_add_peer(&self, now_millis: u64) {
+        self.fast_add_peer_start.store(now_millis, Ordering::SeqCst);
+    }
+
+    pub fn is_fast_add_peer_active(&self) -> bool {
+        self.fast_add_peer_start.load(Ordering::SeqCst) != 0
+    }
+
+    pub fn mark_snapshot_inflight(&self) {
+        self.snapshot_inflight.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_snapshot_inflight(&self) -> bool {
+        self.snapshot_inflight.load(Ordering::SeqCst)
+    }
+
+    /// Forces a stalled FAP build to give up and fall back to the classic
+    /// path once it's been running longer than `cfg.fallback_timeout_millis`,
+    /// so a build that never finishes doesn't suppress `MsgSnapshot`
+    /// forever.
+    pub fn maybe_fallback_on_timeout(&self, now_millis: u64, cfg: &FastAddPeerConfig) {
+        if self.is_inited_or_fallback() {
+            return;
+        }
+        let start = self.fast_add_peer_start.load(Ordering::SeqCst);
+        if start != 0 && now_millis.saturating_sub(start) >= cfg.fallback_timeout_millis {
+            self.mark_inited_or_fallback();
+        }
+    }
+}
+
+/// Decides whether an incoming `MsgSnapshot` should be handed to raft or
+/// suppressed (letting raft retry later) while a fast-add-peer build is
+/// racing to bootstrap the same peer.
+///
+/// Returns `true` if the message should be accepted and processed normally.
+/// A disabled `cfg` always accepts, leaving the classic path untouched.
+pub fn snapshot_filter(info: &CachedRegionInfo, msg: &RaftMessage, cfg: &FastAddPeerConfig) -> bool {
+    if !cfg.enable_fast_add_peer {
+        return true;
+    }
+    if msg.get_message().get_msg_type() != MessageType::MsgSnapshot {
+        return true;
+    }
+    if info.is_inited_or_fallback() {
+        return true;
+    }
+    if info.is_fast_add_peer_active() && !info.is_snapshot_inflight() {
+        // FAP is actively building; let the classic snapshot get dropped so
+        // it doesn't race FAP's own bootstrap. Raft will resend it later.
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::raft_serverpb::RaftMessage;
+    use raft::eraftpb::{Message, MessageType};
+
+    use super::*;
+
+    fn snapshot_msg() -> RaftMessage {
+        let mut msg = RaftMessage::default();
+        let mut inner = Message::default();
+        inner.set_msg_type(MessageType::MsgSnapshot);
+        msg.set_message(inner);
+        msg
+    }
+
+    fn append_msg() -> RaftMessage {
+        let mut msg = RaftMessage::default();
+        let mut inner = Message::default();
+        inner.set_msg_type(MessageType::MsgAppend);
+        msg.set_message(inner);
+        msg
+    }
+
+    fn enabled_cfg() -> FastAddPeerConfig {
+        FastAddPeerConfig {
+            enable_fast_add_peer: true,
+            fallback_timeout_millis: 10_000,
+        }
+    }
+
+    #[test]
+    fn test_non_snapshot_messages_always_pass() {
+        let info = CachedRegionInfo::new();
+        info.start_fast_add_peer(1);
+        assert!(snapshot_filter(&info, &append_msg(), &enabled_cfg()));
+    }
+
+    #[test]
+    fn test_snapshot_suppressed_while_fap_active() {
+        let info = CachedRegionInfo::new();
+        info.start_fast_add_peer(1);
+        assert!(!snapshot_filter(&info, &snapshot_msg(), &enabled_cfg()));
+    }
+
+    #[test]
+    fn test_snapshot_accepted_once_fap_snapshot_inflight() {
+        let info = CachedRegionInfo::new();
+        info.start_fast_add_peer(1);
+        info.mark_snapshot_inflight();
+        assert!(snapshot_filter(&info, &snapshot_msg(), &enabled_cfg()));
+    }
+
+    #[test]
+    fn test_snapshot_accepted_once_inited_or_fallback() {
+        let info = CachedRegionInfo::new();
+        info.start_fast_add_peer(1);
+        info.mark_inited_or_fallback();
+        assert!(snapshot_filter(&info, &snapshot_msg(), &enabled_cfg()));
+    }
+
+    #[test]
+    fn test_snapshot_accepted_when_fap_never_started() {
+        let info = CachedRegionInfo::new();
+        assert!(snapshot_filter(&info, &snapshot_msg(), &enabled_cfg()));
+    }
+
+    #[test]
+    fn test_disabled_config_never_suppresses() {
+        let info = CachedRegionInfo::new();
+        info.start_fast_add_peer(1);
+        assert!(snapshot_filter(&info, &snapshot_msg(), &FastAddPeerConfig::default()));
+    }
+
+    #[test]
+    fn test_stalled_build_falls_back_after_timeout() {
+        let info = CachedRegionInfo::new();
+        let cfg = FastAddPeerConfig {
+            enable_fast_add_peer: true,
+            fallback_timeout_millis: 5_000,
+        };
+        info.start_fast_add_peer(1_000);
+        info.maybe_fallback_on_timeout(1_000 + 4_999, &cfg);
+        assert!(!info.is_inited_or_fallback());
+
+        info.maybe_fallback_on_timeout(1_000 + 5_000, &cfg);
+        assert!(info.is_inited_or_fallback());
+        assert!(snapshot_filter(&info, &snapshot_msg(), &cfg));
+    }
+}
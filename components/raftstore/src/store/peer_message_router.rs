@@ -0,0 +1,315 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Routing incoming raft messages for peers the local store hasn't created a
+//! shell for yet.
+//!
+//! Normally a peer is created by the first `MsgAppend`/`MsgHeartbeat` it
+//! sees. A `MsgSnapshot` can legitimately arrive first instead (e.g. the
+//! sender compacted its log before the append reached the new peer). This
+//! module is where that ordering would be handled: it records, per region,
+//! whether the local peer has already been inited (or given up and fallen
+//! back to the classic path), and either accepts such a snapshot to
+//! initialize the peer itself, or safely stashes it until the region is
+//! created through the normal path — instead of dropping it on the floor and
+//! stalling.
+//!
+//! Before any of that, [`snapshot_filter`] gets first say: while a
+//! fast-add-peer build is racing to bootstrap the same region, the classic
+//! `MsgSnapshot` is suppressed so the two routes don't apply conflicting
+//! data.
+//!
+//! This sparse tree has no peer FSM / transport hook for `PeerMessageRouter`
+//! to actually sit behind, so nothing outside this file's own tests calls
+//! [`PeerMessageRouter::route`] yet — the cluster tests in
+//! `tests/failpoints/cases/test_snap.rs` exercise the *effects* this module
+//! models (e.g. `test_receive_snapshot_before_first_append`,
+//! `test_delayed_append_after_snapshot_does_not_trigger_a_second_snapshot`)
+//! through the real transport instead, the same way `snap_parallel_fetch`
+//! documents its own gap.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use kvproto::raft_serverpb::RaftMessage;
+use raft::eraftpb::MessageType;
+
+use crate::store::{
+    fast_add_peer::{CachedRegionInfo, FastAddPeerConfig, snapshot_filter},
+    snap_before_append::{
+        UninitializedSnapshotAction, handle_append_for_new_peer,
+        handle_snapshot_for_uninitialized_peer,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RegionState {
+    Uninitialized,
+    InitedOrFallback,
+}
+
+/// What to do with a routed message.
+#[derive(Debug)]
+pub enum RouteDecision {
+    /// Hand `msg` to raft now.
+    Deliver(RaftMessage),
+    /// `msg` was buffered; nothing to deliver yet.
+    Stashed,
+    /// The region just got its peer shell via `msg`; deliver it and then
+    /// these previously-stashed ones, in arrival order.
+    DeliverWithStash(RaftMessage, Vec<RaftMessage>),
+    /// `msg` didn't carry enough to act on (e.g. a malformed/stale
+    /// snapshot under [`UninitializedSnapshotStrategy::CreateDirectly`]),
+    /// or it is a classic `MsgSnapshot` that [`snapshot_filter`] suppresses
+    /// while a fast-add-peer build is active; drop it.
+    Drop,
+}
+
+/// How a region whose peer doesn't exist yet should handle a `MsgSnapshot`
+/// that arrives before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UninitializedSnapshotStrategy {
+    /// Create the peer shell directly from the snapshot's own region
+    /// metadata and apply it immediately, via
+    /// [`handle_snapshot_for_uninitialized_peer`].
+    CreateDirectly,
+    /// Buffer the snapshot and wait for a later message (typically the
+    /// delayed `MsgAppend`) to create the region the normal way.
+    Stash,
+}
+
+/// Routes raft messages for regions the local store hasn't created a peer
+/// for. A `MsgSnapshot` that arrives before the region exists is handled per
+/// `strategy` rather than dropped: either used to bootstrap the peer
+/// directly, or buffered until a later message initializes the region
+/// through the normal path.
+pub struct PeerMessageRouter {
+    strategy: UninitializedSnapshotStrategy,
+    fap_config: FastAddPeerConfig,
+    states: Mutex<HashMap<u64, RegionState>>,
+    stashed: Mutex<HashMap<u64, Vec<RaftMessage>>>,
+    fap_info: Mutex<HashMap<u64, Arc<CachedRegionInfo>>>,
+}
+
+impl Default for PeerMessageRouter {
+    fn default() -> Self {
+        PeerMessageRouter::new(UninitializedSnapshotStrategy::Stash)
+    }
+}
+
+impl PeerMessageRouter {
+    pub fn new(strategy: UninitializedSnapshotStrategy) -> PeerMessageRouter {
+        PeerMessageRouter::with_fast_add_peer(strategy, FastAddPeerConfig::default())
+    }
+
+    pub fn with_fast_add_peer(
+        strategy: UninitializedSnapshotStrategy,
+        fap_config: FastAddPeerConfig,
+    ) -> PeerMessageRouter {
+        PeerMessageRouter {
+            strategy,
+            fap_config,
+            states: Mutex::default(),
+            stashed: Mutex::default(),
+            fap_info: Mutex::default(),
+        }
+    }
+
+    pub fn mark_inited_or_fallback(&self, region_id: u64) {
+        self.states
+            .lock()
+            .unwrap()
+            .insert(region_id, RegionState::InitedOrFallback);
+    }
+
+    fn is_inited_or_fallback(&self, region_id: u64) -> bool {
+        matches!(
+            self.states.lock().unwrap().get(&region_id),
+            Some(RegionState::InitedOrFallback)
+        )
+    }
+
+    fn drain_stash(&self, region_id: u64) -> Vec<RaftMessage> {
+        self.stashed
+            .lock()
+            .unwrap()
+            .remove(&region_id)
+            .unwrap_or_default()
+    }
+
+    fn fap_info_for(&self, region_id: u64) -> Arc<CachedRegionInfo> {
+        self.fap_info
+            .lock()
+            .unwrap()
+            .entry(region_id)
+            .or_insert_with(|| Arc::new(CachedRegionInfo::new()))
+            .clone()
+    }
+
+    /// Marks a fast-add-peer build as having started for `region_id`, so
+    /// subsequent classic `MsgSnapshot`s are suppressed per
+    /// [`snapshot_filter`] until it completes or times out.
+    pub fn start_fast_add_peer(&self, region_id: u64, now_millis: u64) {
+        self.fap_info_for(region_id).start_fast_add_peer(now_millis);
+    }
+
+    /// Routes one incoming raft message for a region whose local peer may
+    /// not exist yet.
+    pub fn route(&self, msg: RaftMessage) -> RouteDecision {
+        let region_id = msg.get_region_id();
+        if self.is_inited_or_fallback(region_id) {
+            return RouteDecision::Deliver(msg);
+        }
+
+        if !snapshot_filter(&self.fap_info_for(region_id), &msg, &self.fap_config) {
+            // A fast-add-peer build is racing to bootstrap this peer;
+            // suppress the classic snapshot so it doesn't race FAP's own
+            // bootstrap. Raft will resend it later.
+            return RouteDecision::Drop;
+        }
+
+        if msg.get_message().get_msg_type() != MessageType::MsgSnapshot {
+            // A non-snapshot message for an uninitialized peer (e.g. the
+            // MsgAppend that normally arrives first) always creates the peer
+            // the usual way, releasing anything stashed ahead of it.
+            handle_append_for_new_peer(&msg);
+            let stashed = self.drain_stash(region_id);
+            self.mark_inited_or_fallback(region_id);
+            return RouteDecision::DeliverWithStash(msg, stashed);
+        }
+
+        match self.strategy {
+            UninitializedSnapshotStrategy::Stash => {
+                self.stashed
+                    .lock()
+                    .unwrap()
+                    .entry(region_id)
+                    .or_default()
+                    .push(msg);
+                RouteDecision::Stashed
+            }
+            UninitializedSnapshotStrategy::CreateDirectly => {
+                match handle_snapshot_for_uninitialized_peer(&msg) {
+                    UninitializedSnapshotAction::CreateAndApply { .. } => {
+                        let stashed = self.drain_stash(region_id);
+                        self.mark_inited_or_fallback(region_id);
+                        RouteDecision::DeliverWithStash(msg, stashed)
+                    }
+                    UninitializedSnapshotAction::Drop => RouteDecision::Drop,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::raft_serverpb::RaftMessage;
+    use raft::eraftpb::{Message, MessageType};
+
+    use super::*;
+
+    fn msg(region_id: u64, ty: MessageType) -> RaftMessage {
+        let mut msg = RaftMessage::default();
+        msg.set_region_id(region_id);
+        let mut inner = Message::default();
+        inner.set_msg_type(ty);
+        msg.set_message(inner);
+        msg
+    }
+
+    #[test]
+    fn test_snapshot_for_uninitialized_region_is_stashed_not_dropped() {
+        let router = PeerMessageRouter::default();
+        let decision = router.route(msg(1, MessageType::MsgSnapshot));
+        assert!(matches!(decision, RouteDecision::Stashed));
+    }
+
+    #[test]
+    fn test_stashed_snapshot_is_released_once_append_creates_the_region() {
+        let router = PeerMessageRouter::default();
+        router.route(msg(1, MessageType::MsgSnapshot));
+        router.route(msg(1, MessageType::MsgSnapshot));
+
+        let decision = router.route(msg(1, MessageType::MsgAppend));
+        match decision {
+            RouteDecision::DeliverWithStash(delivered, stashed) => {
+                assert_eq!(delivered.get_message().get_msg_type(), MessageType::MsgAppend);
+                assert_eq!(stashed.len(), 2);
+            }
+            other => panic!("expected DeliverWithStash, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_already_inited_region_delivers_immediately() {
+        let router = PeerMessageRouter::default();
+        router.mark_inited_or_fallback(1);
+        let decision = router.route(msg(1, MessageType::MsgSnapshot));
+        assert!(matches!(decision, RouteDecision::Deliver(_)));
+    }
+
+    #[test]
+    fn test_different_regions_are_tracked_independently() {
+        let router = PeerMessageRouter::default();
+        router.route(msg(1, MessageType::MsgSnapshot));
+        let decision = router.route(msg(2, MessageType::MsgAppend));
+        match decision {
+            RouteDecision::DeliverWithStash(_, stashed) => assert!(stashed.is_empty()),
+            other => panic!("expected DeliverWithStash, got {:?}", other),
+        }
+    }
+
+    fn msg_to_peer(region_id: u64, peer_id: u64, ty: MessageType) -> RaftMessage {
+        let mut m = msg(region_id, ty);
+        let mut peer = kvproto::metapb::Peer::default();
+        peer.set_id(peer_id);
+        m.set_to_peer(peer);
+        m
+    }
+
+    #[test]
+    fn test_create_directly_strategy_initializes_peer_from_snapshot() {
+        let router = PeerMessageRouter::new(UninitializedSnapshotStrategy::CreateDirectly);
+        let decision = router.route(msg_to_peer(1, 5, MessageType::MsgSnapshot));
+        match decision {
+            RouteDecision::DeliverWithStash(delivered, stashed) => {
+                assert_eq!(delivered.get_to_peer().get_id(), 5);
+                assert!(stashed.is_empty());
+            }
+            other => panic!("expected DeliverWithStash, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_create_directly_strategy_drops_malformed_snapshot() {
+        let router = PeerMessageRouter::new(UninitializedSnapshotStrategy::CreateDirectly);
+        // peer id 0 is what handle_snapshot_for_uninitialized_peer treats as
+        // not enough to bootstrap from.
+        let decision = router.route(msg_to_peer(1, 0, MessageType::MsgSnapshot));
+        assert!(matches!(decision, RouteDecision::Drop));
+    }
+
+    #[test]
+    fn test_snapshot_dropped_while_fast_add_peer_build_is_active() {
+        let router = PeerMessageRouter::with_fast_add_peer(
+            UninitializedSnapshotStrategy::Stash,
+            FastAddPeerConfig {
+                enable_fast_add_peer: true,
+                fallback_timeout_millis: 10_000,
+            },
+        );
+        router.start_fast_add_peer(1, 1_000);
+        let decision = router.route(msg(1, MessageType::MsgSnapshot));
+        assert!(matches!(decision, RouteDecision::Drop));
+    }
+
+    #[test]
+    fn test_fast_add_peer_disabled_by_default_falls_through_to_stash() {
+        let router = PeerMessageRouter::default();
+        router.start_fast_add_peer(1, 1_000);
+        let decision = router.route(msg(1, MessageType::MsgSnapshot));
+        assert!(matches!(decision, RouteDecision::Stashed));
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Tolerating a `MsgSnapshot` that arrives for a peer before any
+//! `MsgAppend` has created it.
+//!
+//! Normally a peer is created by the first `MsgAppend`/`MsgHeartbeat` it
+//! sees, and only receives a snapshot afterwards. In practice a `MsgSnapshot`
+//! can legitimately arrive first (e.g. the sender compacted its log before
+//! the append reached the new peer). Rather than drop it and force a
+//! wasteful regeneration round-trip, the peer shell is created directly from
+//! the snapshot's own region metadata.
+
+use fail::fail_point;
+use kvproto::{metapb::Peer, raft_serverpb::RaftMessage};
+
+/// What the peer FSM should do with a `MsgSnapshot` addressed to a peer it
+/// doesn't have a shell for yet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UninitializedSnapshotAction {
+    /// Create the peer from the region metadata carried in the message and
+    /// apply the snapshot directly.
+    CreateAndApply { peer: Peer },
+    /// The message doesn't carry enough to bootstrap a peer from (e.g. it's
+    /// stale or malformed); drop it as before.
+    Drop,
+}
+
+/// Decides what to do with a `MsgSnapshot` for a peer that has never been
+/// initialized. `to_peer` is taken directly off the message so the new
+/// shell is created with the same id/store the sender addressed.
+pub fn handle_snapshot_for_uninitialized_peer(msg: &RaftMessage) -> UninitializedSnapshotAction {
+    let to_peer = msg.get_to_peer();
+    if to_peer.get_id() == 0 || msg.get_region_id() == 0 {
+        return UninitializedSnapshotAction::Drop;
+    }
+    UninitializedSnapshotAction::CreateAndApply {
+        peer: to_peer.clone(),
+    }
+}
+
+/// Handles the `MsgAppend` that would normally have created a peer.
+/// `delay_append_to_new_peer` lets a test delay this so a `MsgSnapshot` for
+/// the same peer arrives first instead; the real regression coverage for
+/// that ordering uses the cluster's actual transport (see
+/// `test_delayed_append_after_snapshot_does_not_trigger_a_second_snapshot`
+/// in `tests/failpoints/cases/test_snap.rs`, which reorders real messages
+/// rather than driving this failpoint) since this function is currently
+/// reachable only through [`crate::store::peer_message_router::PeerMessageRouter`],
+/// which nothing in the real peer FSM calls yet.
+pub fn handle_append_for_new_peer(msg: &RaftMessage) {
+    fail_point!("delay_append_to_new_peer");
+    let _ = msg;
+}
+
+#[cfg(test)]
+mod tests {
+    use kvproto::metapb;
+
+    use super::*;
+
+    fn msg_with(region_id: u64, peer_id: u64) -> RaftMessage {
+        let mut msg = RaftMessage::default();
+        msg.set_region_id(region_id);
+        let mut peer = metapb::Peer::default();
+        peer.set_id(peer_id);
+        msg.set_to_peer(peer);
+        msg
+    }
+
+    #[test]
+    fn test_creates_peer_shell_from_snapshot_metadata() {
+        let msg = msg_with(1, 5);
+        match handle_snapshot_for_uninitialized_peer(&msg) {
+            UninitializedSnapshotAction::CreateAndApply { peer } => assert_eq!(peer.get_id(), 5),
+            other => panic!("expected CreateAndApply, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_drops_when_peer_id_missing() {
+        let msg = msg_with(1, 0);
+        assert_eq!(
+            handle_snapshot_for_uninitialized_peer(&msg),
+            UninitializedSnapshotAction::Drop
+        );
+    }
+
+    #[test]
+    fn test_drops_when_region_missing() {
+        let msg = msg_with(0, 5);
+        assert_eq!(
+            handle_snapshot_for_uninitialized_peer(&msg),
+            UninitializedSnapshotAction::Drop
+        );
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Receiver-driven parallel snapshot fetch.
+//!
+//! A receiving peer splits the snapshot's key range into contiguous
+//! subranges and pulls each one from a different source replica
+//! concurrently, instead of draining a single sender serially. The degree of
+//! parallelism is a [`SnapFetchConfig`] knob rather than a hardcoded
+//! constant, so it can be tuned per deployment.
+
+/// Degree of parallelism for a single snapshot fetch, exposed as a
+/// `raft_store` config knob via [`SnapFetchConfig`].
+pub const DEFAULT_SNAP_FETCH_CONCURRENCY: usize = 4;
+
+/// The subset of `raft_store` config that governs receiver-driven parallel
+/// snapshot fetch. This is what a real `raft_store::Config` would embed;
+/// kept standalone here since this tree has no `Config` struct to extend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapFetchConfig {
+    /// Number of subranges a single snapshot fetch is split into, each
+    /// pulled from a different source concurrently. Clamped to at least 1
+    /// so a misconfigured `0` can't produce a plan with no subranges at all.
+    pub snap_fetch_concurrency: usize,
+}
+
+impl Default for SnapFetchConfig {
+    fn default() -> SnapFetchConfig {
+        SnapFetchConfig {
+            snap_fetch_concurrency: DEFAULT_SNAP_FETCH_CONCURRENCY,
+        }
+    }
+}
+
+impl SnapFetchConfig {
+    /// Builds the fetch plan for one snapshot, using this config's
+    /// parallelism instead of a caller-supplied literal.
+    pub fn build_plan(&self, sources: &[u64]) -> ParallelFetchPlan {
+        let parallelism = self.snap_fetch_concurrency.max(1);
+        ParallelFetchPlan::new(parallelism, sources)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubrangeState {
+    Pending,
+    Downloading(u64),
+    Done,
+}
+
+/// One contiguous, half-open chunk of the overall `["", "")` key range being
+/// fetched from a single source at a time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subrange {
+    pub start: Vec<u8>,
+    pub end: Vec<u8>,
+    pub state: SubrangeState,
+}
+
+/// Tracks every subrange of one snapshot fetch and which source, if any, is
+/// currently serving it.
+#[derive(Clone, Debug, Default)]
+pub struct ParallelFetchPlan {
+    subranges: Vec<Subrange>,
+}
+
+impl ParallelFetchPlan {
+    /// Splits `["", "")` into `parallelism` contiguous subranges by cutting
+    /// the key space at evenly spaced single-byte prefixes. This is a coarse
+    /// split; real key distributions may end up uneven, which is fine since
+    /// each subrange is still fetched independently.
+    pub fn new(parallelism: usize, sources: &[u64]) -> ParallelFetchPlan {
+        assert!(parallelism > 0);
+        let mut bounds: Vec<Vec<u8>> = Vec::with_capacity(parallelism + 1);
+        bounds.push(Vec::new());
+        for i in 1..parallelism {
+            let cut = (i * 256 / parallelism) as u8;
+            bounds.push(vec![cut]);
+        }
+        bounds.push(Vec::new());
+
+        let mut subranges = Vec::with_capacity(parallelism);
+        for i in 0..parallelism {
+            let source = sources.get(i % sources.len().max(1)).copied();
+            subranges.push(Subrange {
+                start: bounds[i].clone(),
+                end: bounds[i + 1].clone(),
+                state: match source {
+                    Some(peer) => SubrangeState::Downloading(peer),
+                    None => SubrangeState::Pending,
+                },
+            });
+        }
+        ParallelFetchPlan { subranges }
+    }
+
+    pub fn subranges(&self) -> &[Subrange] {
+        &self.subranges
+    }
+
+    pub fn mark_done(&mut self, idx: usize) {
+        self.subranges[idx].state = SubrangeState::Done;
+    }
+
+    /// Reassigns a stalled or errored subrange to another live source,
+    /// picking the first candidate that isn't the one that just failed.
+    pub fn reassign(&mut self, idx: usize, failed_source: u64, live_sources: &[u64]) -> bool {
+        let Some(&next) = live_sources.iter().find(|&&s| s != failed_source) else {
+            self.subranges[idx].state = SubrangeState::Pending;
+            return false;
+        };
+        self.subranges[idx].state = SubrangeState::Downloading(next);
+        true
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.subranges.iter().all(|s| s.state == SubrangeState::Done)
+    }
+
+    /// Validates that the completed subranges cover `["", "")` with no gaps
+    /// or overlaps, i.e. each subrange's end equals the next one's start, the
+    /// first starts at `""`, and the last ends at `""` (meaning unbounded).
+    pub fn covers_full_range_with_no_gaps(&self) -> bool {
+        if self.subranges.is_empty() || !self.is_done() {
+            return false;
+        }
+        if !self.subranges[0].start.is_empty() {
+            return false;
+        }
+        if !self.subranges.last().unwrap().end.is_empty() {
+            return false;
+        }
+        self.subranges
+            .windows(2)
+            .all(|w| w[0].end == w[1].start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_assigns_sources_round_robin() {
+        let plan = ParallelFetchPlan::new(4, &[1, 2]);
+        let sources: Vec<_> = plan
+            .subranges()
+            .iter()
+            .map(|s| s.state.clone())
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                SubrangeState::Downloading(1),
+                SubrangeState::Downloading(2),
+                SubrangeState::Downloading(1),
+                SubrangeState::Downloading(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_covers_full_range_once_all_done() {
+        let mut plan = ParallelFetchPlan::new(3, &[1]);
+        assert!(!plan.covers_full_range_with_no_gaps());
+        for i in 0..3 {
+            plan.mark_done(i);
+        }
+        assert!(plan.covers_full_range_with_no_gaps());
+    }
+
+    #[test]
+    fn test_reassign_stalled_subrange_to_another_source() {
+        let mut plan = ParallelFetchPlan::new(2, &[1, 2]);
+        assert!(plan.reassign(0, 1, &[1, 2, 3]));
+        assert_eq!(plan.subranges()[0].state, SubrangeState::Downloading(2));
+    }
+
+    #[test]
+    fn test_reassign_with_no_live_source_goes_pending() {
+        let mut plan = ParallelFetchPlan::new(1, &[1]);
+        assert!(!plan.reassign(0, 1, &[1]));
+        assert_eq!(plan.subranges()[0].state, SubrangeState::Pending);
+    }
+
+    #[test]
+    fn test_default_config_matches_default_concurrency() {
+        assert_eq!(
+            SnapFetchConfig::default().snap_fetch_concurrency,
+            DEFAULT_SNAP_FETCH_CONCURRENCY
+        );
+    }
+
+    #[test]
+    fn test_zero_concurrency_is_clamped_to_one() {
+        let cfg = SnapFetchConfig {
+            snap_fetch_concurrency: 0,
+        };
+        let plan = cfg.build_plan(&[1, 2, 3]);
+        assert_eq!(plan.subranges().len(), 1);
+    }
+
+    /// Stand-in for the requested 5-node-cluster test: this sparse tree has
+    /// no cluster harness to bring one up in, so this instead isolates the
+    /// same scenario at the unit the cluster test would actually be
+    /// exercising — a peer recovering a region by pulling disjoint,
+    /// non-overlapping ranges from multiple live stores concurrently,
+    /// driven by the configured (not hardcoded) degree of parallelism.
+    #[test]
+    fn test_isolated_peer_reconstructs_region_from_multiple_stores_concurrently() {
+        let cluster_store_ids = [2, 3, 4, 5]; // peer 1 is the one recovering
+        let cfg = SnapFetchConfig {
+            snap_fetch_concurrency: 4,
+        };
+        let mut plan = cfg.build_plan(&cluster_store_ids);
+
+        // Every subrange should start out assigned to a distinct live store
+        // rather than funneling through a single sender.
+        let assigned_sources: Vec<_> = plan
+            .subranges()
+            .iter()
+            .map(|s| match s.state {
+                SubrangeState::Downloading(peer) => peer,
+                ref other => panic!("expected Downloading, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(assigned_sources, cluster_store_ids.to_vec());
+
+        // One source (store 3) drops out mid-fetch; its subrange gets
+        // reassigned to a store that's still live instead of stalling.
+        let failed_idx = assigned_sources.iter().position(|&s| s == 3).unwrap();
+        let live_sources = [2, 4, 5];
+        assert!(plan.reassign(failed_idx, 3, &live_sources));
+
+        for i in 0..plan.subranges().len() {
+            plan.mark_done(i);
+        }
+        assert!(plan.is_done());
+        assert!(plan.covers_full_range_with_no_gaps());
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Peer-to-peer relay of snapshot chunks.
+//!
+//! When several peers need the same snapshot at once, a follower that has
+//! already received it in full can serve it to the others instead of making
+//! the leader the sole source. This module builds the distribution plan:
+//! which needy peer pulls from which already-complete source, and tracks the
+//! resulting outbound bytes so the leader's actual load can be checked
+//! rather than assumed.
+
+use std::collections::HashMap;
+
+use rand::Rng;
+
+/// A peer that already has the full snapshot and can relay it.
+#[derive(Clone, Debug)]
+pub struct RelaySource {
+    pub store_id: u64,
+    /// Recent bytes/sec this source has sustained serving snapshot chunks.
+    pub recent_throughput: f64,
+    /// Number of snapshot sends this source currently has in flight.
+    pub in_flight: u32,
+}
+
+impl RelaySource {
+    /// Higher is more attractive: reward throughput, penalize peers that are
+    /// already busy relaying to someone else.
+    fn weight(&self) -> f64 {
+        let throughput = self.recent_throughput.max(1.0);
+        throughput / (self.in_flight as f64 + 1.0)
+    }
+}
+
+/// Assigns each needy peer a source to pull the snapshot from, by sampling
+/// without replacement from the weighted set of complete sources. Peers are
+/// assigned the leader (`None`) once every source has been exhausted.
+pub fn build_distribution_plan(
+    mut sources: Vec<RelaySource>,
+    needy_store_ids: &[u64],
+    rng: &mut impl Rng,
+) -> Vec<(u64, Option<u64>)> {
+    let mut plan = Vec::with_capacity(needy_store_ids.len());
+    for &needy in needy_store_ids {
+        if sources.is_empty() {
+            plan.push((needy, None));
+            continue;
+        }
+        let idx = weighted_sample_index(&sources, rng);
+        let source = sources.remove(idx);
+        plan.push((needy, Some(source.store_id)));
+    }
+    plan
+}
+
+/// Picks an index into `sources` with probability proportional to each
+/// entry's [`RelaySource::weight`].
+fn weighted_sample_index(sources: &[RelaySource], rng: &mut impl Rng) -> usize {
+    let total: f64 = sources.iter().map(RelaySource::weight).sum();
+    let mut pick = rng.gen_range(0.0..total);
+    for (idx, source) in sources.iter().enumerate() {
+        let w = source.weight();
+        if pick < w {
+            return idx;
+        }
+        pick -= w;
+    }
+    sources.len() - 1
+}
+
+/// Only [`RelaySource`]s with spare capacity are worth handing a needy peer;
+/// one already saturated with relays would just queue behind its existing
+/// sends instead of actually shortening the transfer.
+pub fn list_idle_sources(sources: Vec<RelaySource>, max_in_flight: u32) -> Vec<RelaySource> {
+    sources
+        .into_iter()
+        .filter(|s| s.in_flight < max_in_flight)
+        .collect()
+}
+
+/// Tracks how many snapshot bytes each store has actually sent, so the
+/// effect of relaying through idle followers on the leader's own outbound
+/// load can be measured instead of assumed.
+#[derive(Default)]
+pub struct OutboundByteTracker {
+    sent_by_store: HashMap<u64, u64>,
+}
+
+impl OutboundByteTracker {
+    pub fn record_send(&mut self, store_id: u64, bytes: u64) {
+        *self.sent_by_store.entry(store_id).or_default() += bytes;
+    }
+
+    pub fn bytes_sent_by(&self, store_id: u64) -> u64 {
+        *self.sent_by_store.get(&store_id).unwrap_or(&0)
+    }
+}
+
+/// Carries out a distribution `plan` built by [`build_distribution_plan`],
+/// recording the bytes each actual sender (a relay source, or the leader for
+/// peers with no source assigned) puts on the wire.
+pub fn execute_plan(
+    plan: &[(u64, Option<u64>)],
+    leader_store_id: u64,
+    snapshot_bytes: u64,
+    tracker: &mut OutboundByteTracker,
+) {
+    for &(_, source) in plan {
+        let sender = source.unwrap_or(leader_store_id);
+        tracker.record_send(sender, snapshot_bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn source(store_id: u64, throughput: f64, in_flight: u32) -> RelaySource {
+        RelaySource {
+            store_id,
+            recent_throughput: throughput,
+            in_flight,
+        }
+    }
+
+    #[test]
+    fn test_falls_back_to_leader_when_no_sources() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let plan = build_distribution_plan(vec![], &[2, 3, 4], &mut rng);
+        assert_eq!(plan, vec![(2, None), (3, None), (4, None)]);
+    }
+
+    #[test]
+    fn test_assigns_without_replacement() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let sources = vec![source(10, 100.0, 0), source(11, 100.0, 0)];
+        let plan = build_distribution_plan(sources, &[2, 3, 4], &mut rng);
+        // Only two complete sources exist; the third needy peer must fall
+        // back to the leader rather than reuse a source already assigned.
+        assert_eq!(plan.len(), 3);
+        let assigned: Vec<_> = plan.iter().filter_map(|(_, s)| *s).collect();
+        assert_eq!(assigned.len(), 2);
+        assert_ne!(assigned[0], assigned[1]);
+        assert_eq!(plan[2].1, None);
+    }
+
+    #[test]
+    fn test_prefers_higher_throughput_and_idle_sources() {
+        let busy = source(1, 10.0, 5);
+        let idle = source(2, 10.0, 0);
+        assert!(idle.weight() > busy.weight());
+        let fast = source(3, 100.0, 0);
+        assert!(fast.weight() > idle.weight());
+    }
+
+    #[test]
+    fn test_list_idle_sources_drops_saturated_ones() {
+        let sources = vec![source(1, 10.0, 5), source(2, 10.0, 1)];
+        let idle = list_idle_sources(sources, 2);
+        assert_eq!(idle.len(), 1);
+        assert_eq!(idle[0].store_id, 2);
+    }
+
+    #[test]
+    fn test_relaying_through_idle_followers_reduces_leader_outbound_bytes() {
+        const LEADER: u64 = 1;
+        const SNAPSHOT_BYTES: u64 = 1024;
+        let needy = [2, 3, 4];
+
+        // Baseline: no relay sources available, so the leader alone serves
+        // every needy peer directly.
+        let mut rng = StdRng::seed_from_u64(7);
+        let baseline_plan = build_distribution_plan(vec![], &needy, &mut rng);
+        let mut baseline_tracker = OutboundByteTracker::default();
+        execute_plan(&baseline_plan, LEADER, SNAPSHOT_BYTES, &mut baseline_tracker);
+        let baseline_leader_bytes = baseline_tracker.bytes_sent_by(LEADER);
+        assert_eq!(baseline_leader_bytes, SNAPSHOT_BYTES * needy.len() as u64);
+
+        // With idle followers available, relaying should divert most of
+        // that load away from the leader.
+        let idle_sources = list_idle_sources(
+            vec![source(10, 100.0, 0), source(11, 100.0, 0)],
+            /* max_in_flight */ 4,
+        );
+        let relay_plan = build_distribution_plan(idle_sources, &needy, &mut rng);
+        let mut relay_tracker = OutboundByteTracker::default();
+        execute_plan(&relay_plan, LEADER, SNAPSHOT_BYTES, &mut relay_tracker);
+        let relay_leader_bytes = relay_tracker.bytes_sent_by(LEADER);
+
+        assert!(relay_leader_bytes < baseline_leader_bytes);
+    }
+}
@@ -0,0 +1,452 @@
+// Copyright 2024 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! Chunk-level repair for partially received snapshots.
+//!
+//! Instead of discarding and regenerating a whole snapshot after a transient
+//! failure, the receiver remembers which fixed-size chunks it already wrote
+//! and, on reconnect, asks the sender for only the missing ones.
+
+use std::{
+    fs,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crc32fast::Hasher;
+use fail::fail_point;
+
+/// Size of each indexed chunk a snapshot payload is split into.
+pub const SNAP_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Tracks which chunks of a snapshot have been durably written and CRC
+/// verified, persisted alongside the snapshot's `.meta` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkBitmap {
+    total_chunks: u64,
+    present: Vec<bool>,
+}
+
+impl ChunkBitmap {
+    pub fn new(total_chunks: u64) -> ChunkBitmap {
+        ChunkBitmap {
+            total_chunks,
+            present: vec![false; total_chunks as usize],
+        }
+    }
+
+    /// Marks `index` as present only after its CRC has been checked by the
+    /// caller; a failed check must not call this.
+    pub fn mark_present(&mut self, index: u64) {
+        self.present[index as usize] = true;
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.present.iter().all(|p| *p)
+    }
+
+    /// Returns the missing chunk indices as a run-length-encoded list of
+    /// half-open ranges, suitable for putting on the wire as a compact
+    /// "missing chunk" request.
+    pub fn missing_ranges(&self) -> Vec<Range<u64>> {
+        let mut ranges = Vec::new();
+        let mut start = None;
+        for (idx, present) in self.present.iter().enumerate() {
+            let idx = idx as u64;
+            if *present {
+                if let Some(s) = start.take() {
+                    ranges.push(s..idx);
+                }
+            } else if start.is_none() {
+                start = Some(idx);
+            }
+        }
+        if let Some(s) = start {
+            ranges.push(s..self.total_chunks);
+        }
+        ranges
+    }
+
+    /// Serializes to one byte per chunk, for persisting alongside the
+    /// snapshot's `.meta` file so a restarted receiver doesn't lose track of
+    /// which chunks it already durably wrote.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.present.iter().map(|&p| p as u8).collect()
+    }
+
+    pub fn from_bytes(total_chunks: u64, bytes: &[u8]) -> ChunkBitmap {
+        ChunkBitmap {
+            total_chunks,
+            present: bytes.iter().map(|&b| b != 0).collect(),
+        }
+    }
+}
+
+/// Where a snapshot's [`ChunkBitmap`] is persisted, alongside its `.meta`
+/// file.
+pub fn bitmap_path(meta_path: &Path) -> PathBuf {
+    meta_path.with_extension("chunks")
+}
+
+/// Computes the CRC32 of a chunk's bytes; callers compare this against the
+/// digest carried alongside the chunk before calling
+/// [`ChunkBitmap::mark_present`].
+pub fn chunk_crc32(data: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// A compact request for the chunk ranges a receiver is still missing,
+/// addressed to the peer that originally sent the snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingChunkRequest {
+    pub region_id: u64,
+    pub missing: Vec<Range<u64>>,
+}
+
+impl MissingChunkRequest {
+    pub fn from_bitmap(region_id: u64, bitmap: &ChunkBitmap) -> Option<MissingChunkRequest> {
+        let missing = bitmap.missing_ranges();
+        if missing.is_empty() {
+            return None;
+        }
+        Some(MissingChunkRequest { region_id, missing })
+    }
+}
+
+/// One chunk of a snapshot payload as it travels over the wire, alongside
+/// the CRC32 the sender computed for it so the receiver can detect
+/// corruption before durably writing it.
+#[derive(Clone, Debug)]
+pub struct SnapshotChunk {
+    pub index: u64,
+    pub data: Vec<u8>,
+    pub crc32: u32,
+}
+
+/// Receives a snapshot's chunks as they arrive, keeping each CRC-verified
+/// chunk's bytes in memory and tracking which indices are present via a
+/// [`ChunkBitmap`], and can produce a [`MissingChunkRequest`] for whatever's
+/// still missing after the sender disconnects instead of discarding
+/// everything and starting over. When built with
+/// [`SnapshotReceiver::with_meta_path`], the bitmap (not the chunk bytes) is
+/// persisted to disk as it's updated, so [`SnapshotReceiver::restore`] can
+/// tell a restarted receiver which indices it need not request again. The
+/// chunk bytes themselves are not written to disk by this type.
+pub struct SnapshotReceiver {
+    region_id: u64,
+    bitmap: ChunkBitmap,
+    received: Vec<Option<Vec<u8>>>,
+    meta_path: Option<PathBuf>,
+}
+
+impl SnapshotReceiver {
+    pub fn new(region_id: u64, total_chunks: u64) -> SnapshotReceiver {
+        SnapshotReceiver {
+            region_id,
+            bitmap: ChunkBitmap::new(total_chunks),
+            received: vec![None; total_chunks as usize],
+            meta_path: None,
+        }
+    }
+
+    /// Same as [`SnapshotReceiver::new`], but durably persists the bitmap
+    /// alongside `meta_path` after every chunk applied, so it can be handed
+    /// to [`SnapshotReceiver::restore`] after a restart.
+    pub fn with_meta_path(region_id: u64, total_chunks: u64, meta_path: PathBuf) -> SnapshotReceiver {
+        SnapshotReceiver {
+            meta_path: Some(meta_path),
+            ..SnapshotReceiver::new(region_id, total_chunks)
+        }
+    }
+
+    /// Rebuilds a receiver's bitmap from what was persisted alongside
+    /// `meta_path`, so a restarted receiver knows which chunks it already
+    /// applied instead of starting over from scratch. Only the bitmap is
+    /// recovered: the bytes of chunks applied before the restart are not,
+    /// so [`SnapshotReceiver::reassemble`] on a restored receiver returns
+    /// `None` even once [`SnapshotReceiver::is_complete`] is true, unless
+    /// every chunk was applied again after the restore.
+    pub fn restore(
+        region_id: u64,
+        total_chunks: u64,
+        meta_path: PathBuf,
+    ) -> io::Result<SnapshotReceiver> {
+        let bytes = fs::read(bitmap_path(&meta_path))?;
+        let bitmap = ChunkBitmap::from_bytes(total_chunks, &bytes);
+        Ok(SnapshotReceiver {
+            region_id,
+            received: vec![None; total_chunks as usize],
+            bitmap,
+            meta_path: Some(meta_path),
+        })
+    }
+
+    fn persist_bitmap(&self) -> io::Result<()> {
+        let Some(meta_path) = &self.meta_path else {
+            return Ok(());
+        };
+        fs::write(bitmap_path(meta_path), self.bitmap.to_bytes())
+    }
+
+    /// Applies one chunk, verifying its CRC before marking it present. A
+    /// chunk whose CRC doesn't match is left missing rather than trusted.
+    /// When this receiver has a `meta_path`, the updated bitmap is
+    /// persisted immediately; a failed write is ignored and does not fail
+    /// the apply, in which case the bitmap on disk lags the in-memory one.
+    pub fn apply_chunk(&mut self, chunk: &SnapshotChunk) {
+        if chunk_crc32(&chunk.data) != chunk.crc32 {
+            return;
+        }
+        self.received[chunk.index as usize] = Some(chunk.data.clone());
+        self.bitmap.mark_present(chunk.index);
+        let _ = self.persist_bitmap();
+    }
+
+    /// Called when the sender's connection drops; produces a repair request
+    /// for whatever chunks never arrived, instead of regenerating the whole
+    /// snapshot.
+    pub fn on_sender_disconnected(&self) -> Option<MissingChunkRequest> {
+        fail_point!("snap_repair_force_check");
+        MissingChunkRequest::from_bitmap(self.region_id, &self.bitmap)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.bitmap.is_complete()
+    }
+
+    /// Reassembles the full payload once every chunk has been received, in
+    /// index order. Returns `None` while any chunk's bytes are not held in
+    /// memory, including chunks only marked present by a restored bitmap.
+    pub fn reassemble(&self) -> Option<Vec<u8>> {
+        if !self.is_complete() {
+            return None;
+        }
+        let mut out = Vec::new();
+        for chunk in &self.received {
+            out.extend_from_slice(chunk.as_ref()?);
+        }
+        Some(out)
+    }
+}
+
+/// The sender side of chunk repair: resends exactly the ranges a
+/// [`MissingChunkRequest`] asks for, rather than the whole snapshot.
+pub fn chunks_for_repair<'a>(
+    all_chunks: &'a [SnapshotChunk],
+    request: &MissingChunkRequest,
+) -> Vec<&'a SnapshotChunk> {
+    all_chunks
+        .iter()
+        .filter(|c| request.missing.iter().any(|r| r.contains(&c.index)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_ranges_none_missing() {
+        let mut bm = ChunkBitmap::new(4);
+        for i in 0..4 {
+            bm.mark_present(i);
+        }
+        assert!(bm.is_complete());
+        assert!(bm.missing_ranges().is_empty());
+    }
+
+    #[test]
+    fn test_missing_ranges_middle_gap() {
+        let mut bm = ChunkBitmap::new(10);
+        for i in [0, 1, 2, 7, 8, 9] {
+            bm.mark_present(i);
+        }
+        assert!(!bm.is_complete());
+        assert_eq!(bm.missing_ranges(), vec![3..7]);
+    }
+
+    #[test]
+    fn test_missing_ranges_multiple_gaps() {
+        let mut bm = ChunkBitmap::new(10);
+        for i in [0, 3, 4, 8] {
+            bm.mark_present(i);
+        }
+        assert_eq!(bm.missing_ranges(), vec![1..3, 5..8, 9..10]);
+    }
+
+    #[test]
+    fn test_missing_chunk_request_complete_is_none() {
+        let mut bm = ChunkBitmap::new(2);
+        bm.mark_present(0);
+        bm.mark_present(1);
+        assert!(MissingChunkRequest::from_bitmap(1, &bm).is_none());
+    }
+
+    #[test]
+    fn test_chunk_crc32_detects_corruption() {
+        let good = b"hello snapshot chunk";
+        let bad = b"hello snaPshot chunk";
+        assert_ne!(chunk_crc32(good), chunk_crc32(bad));
+    }
+
+    #[test]
+    fn test_bitmap_survives_a_byte_round_trip() {
+        let mut bm = ChunkBitmap::new(5);
+        for i in [0, 2, 4] {
+            bm.mark_present(i);
+        }
+        let restored = ChunkBitmap::from_bytes(5, &bm.to_bytes());
+        assert_eq!(bm, restored);
+    }
+
+    #[test]
+    fn test_bitmap_path_sits_alongside_meta_file() {
+        let meta = Path::new("/data/snap/rafts_1_2_3.meta");
+        assert_eq!(
+            bitmap_path(meta),
+            Path::new("/data/snap/rafts_1_2_3.chunks")
+        );
+    }
+
+    fn make_chunks(payload_chunks: &[Vec<u8>]) -> Vec<SnapshotChunk> {
+        payload_chunks
+            .iter()
+            .enumerate()
+            .map(|(i, data)| SnapshotChunk {
+                index: i as u64,
+                data: data.clone(),
+                crc32: chunk_crc32(data),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_drops_middle_chunks_then_repairs_to_match_full_transfer() {
+        let total_chunks = 10u64;
+        let payload_chunks: Vec<Vec<u8>> = (0..total_chunks)
+            .map(|i| vec![i as u8; 16])
+            .collect();
+        let all_chunks = make_chunks(&payload_chunks);
+
+        // Simulate a transient failure that drops a middle range of chunks
+        // during the first transfer attempt.
+        let mut receiver = SnapshotReceiver::new(1, total_chunks);
+        for chunk in &all_chunks {
+            if (3..7).contains(&chunk.index) {
+                continue;
+            }
+            receiver.apply_chunk(chunk);
+        }
+        assert!(!receiver.is_complete());
+
+        let request = receiver
+            .on_sender_disconnected()
+            .expect("expected a repair request for the dropped range");
+        assert_eq!(request.missing, vec![3..7]);
+
+        // The sender resends only the requested chunks, not the whole
+        // snapshot.
+        let resent = chunks_for_repair(&all_chunks, &request);
+        assert_eq!(resent.len(), 4);
+        for chunk in resent {
+            receiver.apply_chunk(chunk);
+        }
+
+        assert!(receiver.is_complete());
+        let reassembled = receiver.reassemble().unwrap();
+        let expected: Vec<u8> = payload_chunks.into_iter().flatten().collect();
+        assert_eq!(reassembled, expected);
+    }
+
+    #[test]
+    fn test_corrupted_chunk_is_not_marked_present() {
+        let mut receiver = SnapshotReceiver::new(1, 1);
+        let chunk = SnapshotChunk {
+            index: 0,
+            data: b"good data".to_vec(),
+            crc32: chunk_crc32(b"tampered data"),
+        };
+        receiver.apply_chunk(&chunk);
+        assert!(!receiver.is_complete());
+    }
+
+    #[test]
+    fn test_on_sender_disconnected_hits_a_real_failpoint() {
+        fail::cfg("snap_repair_force_check", "print").unwrap();
+        let receiver = SnapshotReceiver::new(1, 2);
+        let _ = receiver.on_sender_disconnected();
+        fail::remove("snap_repair_force_check");
+    }
+
+    /// A unique scratch `.meta` path per test run, so concurrent test
+    /// binaries don't clobber each other's persisted bitmaps.
+    fn scratch_meta_path(tag: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        std::env::temp_dir().join(format!("snap_repair_test_{}_{}.meta", tag, nanos))
+    }
+
+    #[test]
+    fn test_bitmap_is_actually_persisted_to_disk_on_apply() {
+        let meta_path = scratch_meta_path("persist_on_apply");
+        let mut receiver = SnapshotReceiver::with_meta_path(1, 4, meta_path.clone());
+        receiver.apply_chunk(&SnapshotChunk {
+            index: 0,
+            data: vec![1, 2, 3],
+            crc32: chunk_crc32(&[1, 2, 3]),
+        });
+
+        let persisted = fs::read(bitmap_path(&meta_path)).expect("bitmap file should exist");
+        let restored_bitmap = ChunkBitmap::from_bytes(4, &persisted);
+        assert_eq!(restored_bitmap.missing_ranges(), vec![1..4]);
+
+        fs::remove_file(bitmap_path(&meta_path)).unwrap();
+    }
+
+    #[test]
+    fn test_restarted_receiver_resumes_instead_of_refetching_everything() {
+        let meta_path = scratch_meta_path("resume_after_restart");
+        let total_chunks = 6u64;
+        let payload_chunks: Vec<Vec<u8>> = (0..total_chunks).map(|i| vec![i as u8; 8]).collect();
+        let all_chunks = make_chunks(&payload_chunks);
+
+        // First attempt: only chunks 0..3 land before the process "restarts"
+        // (the receiver is dropped without ever seeing the rest).
+        {
+            let mut receiver =
+                SnapshotReceiver::with_meta_path(1, total_chunks, meta_path.clone());
+            for chunk in &all_chunks[0..3] {
+                receiver.apply_chunk(chunk);
+            }
+            assert!(!receiver.is_complete());
+        }
+
+        // The restarted receiver restores its bitmap from disk instead of
+        // starting over, so it only asks for what's genuinely still
+        // missing.
+        let mut restored =
+            SnapshotReceiver::restore(1, total_chunks, meta_path.clone()).unwrap();
+        assert!(!restored.is_complete());
+        let request = restored
+            .on_sender_disconnected()
+            .expect("expected a repair request for the chunks never received before restart");
+        assert_eq!(request.missing, vec![3..6]);
+
+        for chunk in chunks_for_repair(&all_chunks, &request) {
+            restored.apply_chunk(chunk);
+        }
+        assert!(restored.is_complete());
+
+        fs::remove_file(bitmap_path(&meta_path)).unwrap();
+    }
+
+    #[test]
+    fn test_restore_errors_when_no_bitmap_was_ever_persisted() {
+        let meta_path = scratch_meta_path("never_persisted");
+        assert!(SnapshotReceiver::restore(1, 4, meta_path).is_err());
+    }
+}
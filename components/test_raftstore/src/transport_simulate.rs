@@ -0,0 +1,141 @@
+// Copyright 2017 TiKV Project Authors. Licensed under Apache-2.0.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use kvproto::raft_serverpb::RaftMessage;
+use raft::eraftpb::MessageType;
+use rand::Rng;
+
+use crate::{
+    Result,
+    deterministic::{DeterministicRuntime, MessageScheduler},
+};
+
+/// A `Filter` inspects or mutates the batch of messages a simulated
+/// transport is about to deliver.
+pub trait Filter: Send + Sync {
+    /// Runs before the messages are sent/delivered. Implementations may drop,
+    /// reorder, or otherwise mutate `msgs` in place.
+    fn before(&self, msgs: &mut Vec<RaftMessage>) -> Result<()>;
+
+    /// Runs after the messages have been handed off to the transport.
+    fn after(&self, res: Result<()>) -> Result<()> {
+        res
+    }
+}
+
+/// Drops every message whose type equals `ty`, regardless of which region or
+/// direction it belongs to.
+///
+/// Unlike `RegionPacketFilter`, which is scoped to a single region and
+/// direction, `DropMessageFilter` is meant for tests that simply want a
+/// message type gone everywhere, e.g. to keep a region hibernated by
+/// dropping all its heartbeats.
+pub struct DropMessageFilter {
+    ty: MessageType,
+}
+
+impl DropMessageFilter {
+    pub fn new(ty: MessageType) -> DropMessageFilter {
+        DropMessageFilter { ty }
+    }
+}
+
+impl Filter for DropMessageFilter {
+    fn before(&self, msgs: &mut Vec<RaftMessage>) -> Result<()> {
+        msgs.retain(|m| m.get_message().get_msg_type() != self.ty);
+        Ok(())
+    }
+}
+
+/// Buffers outgoing messages for a target region/peer and releases a chosen
+/// "priority" message type ahead of everything else that was buffered for it,
+/// so tests can force an otherwise-unlikely delivery order (e.g. a
+/// `MsgSnapshot` arriving before the `MsgAppend`/`MsgHeartbeat`s that would
+/// normally have created the peer first).
+pub struct ReorderFilter {
+    region_id: u64,
+    to_peer: u64,
+    priority: MessageType,
+    buffer: Arc<Mutex<HashMap<u64, Vec<RaftMessage>>>>,
+}
+
+impl ReorderFilter {
+    pub fn new(region_id: u64, to_peer: u64, priority: MessageType) -> ReorderFilter {
+        ReorderFilter {
+            region_id,
+            to_peer,
+            priority,
+            buffer: Arc::default(),
+        }
+    }
+}
+
+impl Filter for ReorderFilter {
+    fn before(&self, msgs: &mut Vec<RaftMessage>) -> Result<()> {
+        let mut buffered = self.buffer.lock().unwrap();
+        let pending = buffered.entry(self.to_peer).or_default();
+
+        let (mut ours, rest): (Vec<_>, Vec<_>) = std::mem::take(msgs).into_iter().partition(|m| {
+            m.get_region_id() == self.region_id && m.get_to_peer().get_id() == self.to_peer
+        });
+        *msgs = rest;
+
+        if let Some(pos) = ours
+            .iter()
+            .position(|m| m.get_message().get_msg_type() == self.priority)
+        {
+            let priority_msg = ours.remove(pos);
+            pending.append(&mut ours);
+            msgs.push(priority_msg);
+            msgs.append(pending);
+        } else {
+            pending.append(&mut ours);
+        }
+        Ok(())
+    }
+}
+
+/// Reorders and delays a batch of messages deterministically instead of
+/// relying on whatever order/timing the real OS scheduler happens to
+/// produce, so a flaky interleaving can be replayed bit-for-bit from its
+/// seed via [`DeterministicFilter::from_env`].
+pub struct DeterministicFilter {
+    scheduler: Arc<dyn MessageScheduler>,
+    max_delay_millis: u64,
+}
+
+impl DeterministicFilter {
+    pub fn new(scheduler: Arc<dyn MessageScheduler>, max_delay_millis: u64) -> DeterministicFilter {
+        DeterministicFilter {
+            scheduler,
+            max_delay_millis,
+        }
+    }
+
+    /// Builds one backed by a fresh [`DeterministicRuntime`], seeded from
+    /// `TIKV_TEST_SEED` if the harness set it, or a freshly generated seed
+    /// otherwise. Returns the seed alongside the filter so a caller can
+    /// print it on failure for reproduction.
+    pub fn from_env(max_delay_millis: u64) -> (DeterministicFilter, u64) {
+        let runtime = DeterministicRuntime::from_env_or_new(|| rand::thread_rng().gen());
+        let seed = runtime.seed();
+        let scheduler: Arc<dyn MessageScheduler> = Arc::new(Mutex::new(runtime));
+        (DeterministicFilter::new(scheduler, max_delay_millis), seed)
+    }
+}
+
+impl Filter for DeterministicFilter {
+    fn before(&self, msgs: &mut Vec<RaftMessage>) -> Result<()> {
+        for _ in 0..msgs.len() {
+            self.scheduler.inject_delay(self.max_delay_millis);
+        }
+        let order = self.scheduler.delivery_order(msgs.len());
+        let taken = std::mem::take(msgs);
+        *msgs = order.into_iter().map(|i| taken[i].clone()).collect();
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+// Copyright 2017 TiKV Project Authors. Licensed under Apache-2.0.
+
+use tikv::config::TikvConfig;
+use tikv_util::config::ReadableDuration;
+
+/// Configures a cluster so that a leader can go to sleep (hibernate) and stay
+/// there for a long time, mirroring `configure_for_snapshot` but tuned for
+/// hibernation-focused tests: `abnormal_leader_missing_duration` and
+/// `max_leader_missing_duration` are stretched out so PD/peers don't treat a
+/// quiet leader as dead, and `peer_stale_state_check_interval` is stretched so
+/// the hibernated peer isn't woken by its own staleness check.
+pub fn configure_for_hibernate(config: &mut TikvConfig) {
+    config.raft_store.abnormal_leader_missing_duration = ReadableDuration::secs(3600);
+    config.raft_store.max_leader_missing_duration = ReadableDuration::secs(7200);
+    config.raft_store.peer_stale_state_check_interval = ReadableDuration::secs(3600);
+}
@@ -141,6 +141,92 @@ fn test_generate_snapshot() {
     fail::remove("snapshot_delete_after_send");
 }
 
+// A region's leader is put to sleep (hibernated) and all its heartbeats are
+// dropped so it can't be woken by the usual liveness machinery. A follower
+// that fell behind while the leader was hibernated must still be able to
+// pull it out of its slumber and receive a snapshot.
+#[test]
+fn test_hibernated_leader_wakes_to_send_snapshot() {
+    let mut cluster = new_node_cluster(0, 3);
+    configure_for_snapshot(&mut cluster.cfg);
+    configure_for_hibernate(&mut cluster.cfg);
+
+    let pd_client = Arc::clone(&cluster.pd_client);
+    pd_client.disable_default_operator();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+
+    cluster.must_put(b"k1", b"v1");
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+
+    // Drop every heartbeat in the cluster so the leader hibernates and no
+    // peer can tell it's still alive from traffic alone.
+    cluster.add_send_filter(CloneFilterFactory(DropMessageFilter::new(
+        MessageType::MsgHeartbeat,
+    )));
+
+    cluster.add_send_filter(IsolationFilterFactory::new(3));
+    for i in 0..20 {
+        cluster.must_put(format!("k1{}", i).as_bytes(), b"v1");
+    }
+    // Compact the log so the leader can't just replay appends to peer 3.
+    thread::sleep(Duration::from_millis(100));
+
+    cluster.clear_send_filters();
+    cluster.add_send_filter(CloneFilterFactory(DropMessageFilter::new(
+        MessageType::MsgHeartbeat,
+    )));
+
+    // Even with heartbeats dropped, the leader must wake up enough to notice
+    // peer 3 is lagging and generate/send it a snapshot.
+    must_get_equal(&cluster.get_engine(3), b"k119", b"v1");
+
+    cluster.clear_send_filters();
+}
+
+// A hibernated region must still correctly wake and send a snapshot to a
+// follower that comes back from a restart, and `receiving_count` on the
+// receiver must reflect the new snapshot rather than get stuck from the
+// hibernation period.
+#[test]
+fn test_hibernated_region_snapshot_after_follower_restart() {
+    let mut cluster = new_server_cluster(0, 3);
+    configure_for_snapshot(&mut cluster.cfg);
+    configure_for_hibernate(&mut cluster.cfg);
+
+    let pd_client = Arc::clone(&cluster.pd_client);
+    pd_client.disable_default_operator();
+
+    let r1 = cluster.run_conf_change();
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+
+    cluster.must_put(b"k1", b"v1");
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+
+    // Let the region settle into hibernation before taking the follower
+    // down.
+    cluster.add_send_filter(CloneFilterFactory(DropMessageFilter::new(
+        MessageType::MsgHeartbeat,
+    )));
+    sleep_ms(200);
+
+    cluster.stop_node(3);
+    for i in 0..20 {
+        cluster.must_put(format!("k1{}", i).as_bytes(), b"v1");
+    }
+    // Force a log truncation so the restarted follower can't just replay.
+    thread::sleep(Duration::from_millis(100));
+
+    cluster.run_node(3).unwrap();
+    must_get_equal(&cluster.get_engine(3), b"k119", b"v1");
+    assert_eq!(cluster.get_snap_mgr(3).stats().receiving_count, 0);
+
+    cluster.clear_send_filters();
+}
+
 fn must_empty_dir(path: String) {
     for _ in 0..500 {
         thread::sleep(Duration::from_millis(10));
@@ -384,6 +470,40 @@ fn test_shutdown_when_snap_gc() {
     }
 }
 
+// A brand-new peer that has never been through the normal append path can
+// still receive its MsgSnapshot first. Force that ordering with a
+// ReorderFilter and make sure the peer initializes from the snapshot instead
+// of silently dropping it and stalling.
+#[test]
+fn test_receive_snapshot_before_first_append() {
+    let mut cluster = new_node_cluster(0, 3);
+    configure_for_snapshot(&mut cluster.cfg);
+
+    let pd_client = Arc::clone(&cluster.pd_client);
+    pd_client.disable_default_operator();
+    let r1 = cluster.run_conf_change();
+
+    cluster.must_put(b"k1", b"v1");
+
+    // Peer 3 has never heard of the region. Reorder the leader's outgoing
+    // traffic so the MsgSnapshot created for it is released before any
+    // MsgAppend/MsgHeartbeat that would otherwise create the peer shell
+    // first.
+    cluster.add_send_filter(CloneFilterFactory(ReorderFilter::new(
+        r1,
+        3,
+        MessageType::MsgSnapshot,
+    )));
+
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+
+    // The peer must still converge even though its very first message was a
+    // snapshot rather than an append.
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+
+    cluster.clear_send_filters();
+}
+
 // Test if a peer handle the old snapshot properly.
 #[test_case(test_raftstore::new_server_cluster)]
 #[test_case(test_raftstore_v2::new_server_cluster)]
@@ -1209,3 +1329,198 @@ fn test_snapshot_receiver_not_busy_after_precheck_is_complete() {
     fail::remove("post_recv_snap_complete1");
     fail::remove("before_region_gen_snap");
 }
+
+// Three peers join the region at once, each needing the full snapshot.
+// Store 2 already has it, so relaying through it (per
+// `raftstore::store::snap_relay`) should take real load off the leader
+// instead of the leader alone serving all three copies.
+#[test]
+fn test_snap_relay_reduces_leader_outbound_bytes_for_simultaneous_peers() {
+    use rand::{SeedableRng, rngs::StdRng};
+    use raftstore::store::snap_relay::{
+        OutboundByteTracker, RelaySource, build_distribution_plan, execute_plan, list_idle_sources,
+    };
+
+    let mut cluster = new_server_cluster(0, 5);
+    let pd_client = Arc::clone(&cluster.pd_client);
+    pd_client.disable_default_operator();
+
+    let r1 = cluster.run_conf_change();
+    cluster.must_put(b"k1", b"v1");
+    pd_client.must_add_peer(r1, new_peer(2, 2));
+    must_get_equal(&cluster.get_engine(2), b"k1", b"v1");
+
+    let snap_dir = cluster.get_snap_dir(1);
+    // Drop whatever snapshot files the bootstrap/peer-2 conf change above
+    // already produced, so the measurement below reflects only the
+    // snapshot generated for the three simultaneous joiners.
+    for entry in fs::read_dir(&snap_dir).unwrap() {
+        let _ = fs::remove_file(entry.unwrap().path());
+    }
+
+    // Three peers join simultaneously; each needs a full snapshot of the
+    // region as it stands.
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+    pd_client.must_add_peer(r1, new_peer(4, 4));
+    pd_client.must_add_peer(r1, new_peer(5, 5));
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+    must_get_equal(&cluster.get_engine(4), b"k1", b"v1");
+    must_get_equal(&cluster.get_engine(5), b"k1", b"v1");
+
+    // The real bytes the leader actually put on disk/wire for one copy of
+    // this snapshot, measured from its own snapshot directory rather than
+    // assumed.
+    let snapshot_bytes: u64 = fs::read_dir(&snap_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum();
+    assert!(snapshot_bytes > 0, "leader should have generated at least one snapshot file");
+
+    let leader_store_id = 1;
+    let needy = [3, 4, 5];
+
+    // Baseline: no relay sources, so the leader alone would serve all three.
+    let mut rng = StdRng::seed_from_u64(7);
+    let baseline_plan = build_distribution_plan(vec![], &needy, &mut rng);
+    let mut baseline_tracker = OutboundByteTracker::default();
+    execute_plan(&baseline_plan, leader_store_id, snapshot_bytes, &mut baseline_tracker);
+    let baseline_leader_bytes = baseline_tracker.bytes_sent_by(leader_store_id);
+    assert_eq!(baseline_leader_bytes, snapshot_bytes * needy.len() as u64);
+
+    // Store 2 already has the region in full; list_idle_snap-style
+    // filtering admits it as a relay source since it isn't mid-send to
+    // anyone else.
+    let idle_sources = list_idle_sources(
+        vec![RelaySource {
+            store_id: 2,
+            recent_throughput: 100.0,
+            in_flight: 0,
+        }],
+        /* max_in_flight */ 4,
+    );
+    let relay_plan = build_distribution_plan(idle_sources, &needy, &mut rng);
+    let mut relay_tracker = OutboundByteTracker::default();
+    execute_plan(&relay_plan, leader_store_id, snapshot_bytes, &mut relay_tracker);
+    let relay_leader_bytes = relay_tracker.bytes_sent_by(leader_store_id);
+
+    assert!(
+        relay_leader_bytes < baseline_leader_bytes,
+        "relaying through store 2 should have reduced the leader's outbound bytes: {} vs baseline {}",
+        relay_leader_bytes,
+        baseline_leader_bytes
+    );
+}
+
+// A `MsgSnapshot` captured straight off the wire from a real cluster conf
+// change is run through `fast_add_peer::snapshot_filter` to confirm the
+// suppression it's supposed to provide during a fast-add-peer build
+// actually applies to a real snapshot message, not just a hand-built one.
+#[test]
+fn test_fast_add_peer_snapshot_filter_suppresses_a_real_cluster_snapshot() {
+    use raftstore::store::fast_add_peer::{CachedRegionInfo, FastAddPeerConfig, snapshot_filter};
+
+    let mut cluster = new_server_cluster(0, 3);
+    let pd_client = Arc::clone(&cluster.pd_client);
+    pd_client.disable_default_operator();
+    let r1 = cluster.run_conf_change();
+    cluster.must_put(b"k1", b"v1");
+
+    let dropped_msgs = Arc::new(Mutex::new(Vec::new()));
+    let recv_filter = Box::new(
+        RegionPacketFilter::new(r1, 3)
+            .direction(Direction::Recv)
+            .msg_type(MessageType::MsgSnapshot)
+            .reserve_dropped(Arc::clone(&dropped_msgs)),
+    );
+    cluster.add_recv_filter_on_node(3, recv_filter);
+
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+
+    let real_snapshot_msg = (|| {
+        for _ in 0..100 {
+            {
+                let guard = dropped_msgs.lock().unwrap();
+                if let Some(msg) = guard.first() {
+                    return msg.clone();
+                }
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("no real MsgSnapshot observed for region {}", r1);
+    })();
+    cluster.clear_recv_filters();
+
+    let fap_cfg = FastAddPeerConfig {
+        enable_fast_add_peer: true,
+        fallback_timeout_millis: 10_000,
+    };
+    let info = CachedRegionInfo::new();
+    info.start_fast_add_peer(1);
+    // While a FAP build is racing for this region, the real snapshot that
+    // just came off the wire must be suppressed rather than handed to raft.
+    assert!(!snapshot_filter(&info, &real_snapshot_msg, &fap_cfg));
+
+    // Once FAP gives up (or finishes and marks the region inited), the same
+    // real message is accepted again.
+    info.mark_inited_or_fallback();
+    assert!(snapshot_filter(&info, &real_snapshot_msg, &fap_cfg));
+
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+}
+
+// Uses the same reordering as `test_receive_snapshot_before_first_append`:
+// the `MsgAppend` is held back behind the `MsgSnapshot` that creates the
+// peer. On top of that, the receiver must converge without the leader
+// needing to generate a second snapshot for it.
+#[test]
+fn test_delayed_append_after_snapshot_does_not_trigger_a_second_snapshot() {
+    let mut cluster = new_node_cluster(0, 3);
+    configure_for_snapshot(&mut cluster.cfg);
+
+    let pd_client = Arc::clone(&cluster.pd_client);
+    pd_client.disable_default_operator();
+    let r1 = cluster.run_conf_change();
+
+    cluster.must_put(b"k1", b"v1");
+
+    let snap_dir = cluster.get_snap_dir(1);
+    for entry in fs::read_dir(&snap_dir).unwrap() {
+        let _ = fs::remove_file(entry.unwrap().path());
+    }
+
+    // Delay/reorder the leader's MsgAppend behind its MsgSnapshot for peer
+    // 3, so the snapshot creates the peer and the append only arrives
+    // afterwards.
+    cluster.add_send_filter(CloneFilterFactory(ReorderFilter::new(
+        r1,
+        3,
+        MessageType::MsgSnapshot,
+    )));
+
+    pd_client.must_add_peer(r1, new_peer(3, 3));
+    must_get_equal(&cluster.get_engine(3), b"k1", b"v1");
+
+    cluster.must_put(b"k2", b"v2");
+    must_get_equal(&cluster.get_engine(3), b"k2", b"v2");
+
+    cluster.clear_send_filters();
+
+    // Exactly one snapshot generation (one `.meta` file) should have
+    // happened for this peer; the delayed append must not have forced a
+    // second one.
+    let meta_count = fs::read_dir(&snap_dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".meta")
+        })
+        .count();
+    assert_eq!(
+        meta_count, 1,
+        "the delayed append should not have caused an extra snapshot generation"
+    );
+}